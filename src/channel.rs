@@ -2,10 +2,22 @@ use tokio_io::{AsyncRead, AsyncWrite};
 use futures::{self, Async, Future, Poll};
 use std::io::prelude::*;
 use std::io;
+use std::fmt;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use thrussh;
 use session;
 use SharableConnection;
 
+/// The initial per-channel send window advertised by the remote end, per RFC 4254. We start out
+/// assuming this much, and then track the real window as `window_adjust` messages arrive.
+const INITIAL_WINDOW_SIZE: u32 = 2097152;
+
+/// The terminal size we assume for recordings of channels that never requested a PTY.
+const DEFAULT_RECORDING_SIZE: (u16, u16) = (80, 24);
+
+/// The `$TERM` we report in recordings of channels that never requested a PTY.
+const DEFAULT_RECORDING_TERM: &str = "xterm";
+
 pub(crate) struct State {
     pub(crate) closed: bool,
 
@@ -14,11 +26,27 @@ pub(crate) struct State {
     pub(crate) data: Vec<u8>,
     pub(crate) eof: bool,
 
+    pub(crate) ext_read_notify: Option<futures::task::Task>,
+    pub(crate) ext_data_start: usize,
+    pub(crate) ext_data: Vec<u8>,
+    // whether a Stderr handle aliasing the above has already been handed out; see Channel::stderr
+    pub(crate) stderr_taken: bool,
+
     pub(crate) exit_notify: Option<futures::task::Task>,
-    pub(crate) exit_status: Option<u32>,
+    pub(crate) exit_status: Option<ExitStatus>,
 
     pub(crate) open_notify: Option<futures::task::Task>,
     pub(crate) open_state: Option<Result<(), thrussh::ChannelOpenFailure>>,
+
+    // the number of bytes we're still allowed to send before the remote grows our window
+    pub(crate) window: u32,
+    pub(crate) write_notify: Option<futures::task::Task>,
+
+    // the terminal size and $TERM requested for this channel's PTY, if any; used to size new
+    // recordings and populate their "env" header
+    pub(crate) pty_dims: Option<(u16, u16)>,
+    pub(crate) pty_term: Option<String>,
+    pub(crate) recorder: Option<Recorder>,
 }
 
 impl Default for State {
@@ -31,22 +59,193 @@ impl Default for State {
             data: Vec::new(),
             eof: false,
 
+            ext_read_notify: None,
+            ext_data_start: 0,
+            ext_data: Vec::new(),
+            stderr_taken: false,
+
             exit_notify: None,
             exit_status: None,
 
             open_notify: None,
             open_state: None,
+
+            window: INITIAL_WINDOW_SIZE,
+            write_notify: None,
+
+            pty_dims: None,
+            pty_term: None,
+            recorder: None,
+        }
+    }
+}
+
+impl State {
+    /// Record a chunk of data the remote sent us (stdout or stderr), if recording is enabled.
+    pub(crate) fn record_output(&mut self, data: &[u8]) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.event(b'o', data);
+        }
+    }
+
+    /// Record a chunk of data we sent the remote (stdin), if recording is enabled.
+    pub(crate) fn record_input(&mut self, data: &[u8]) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.event(b'i', data);
+        }
+    }
+
+    /// Apply a window-adjust notification from the remote.
+    ///
+    /// Per [RFC 4254 section 5.2](https://tools.ietf.org/html/rfc4254#section-5.2), a channel
+    /// window adjustment is a *cumulative* number of additional bytes we're now allowed to send,
+    /// not the new total window size, so we must add it to (not replace) the window we're
+    /// already tracking; otherwise any bytes of the previous grant we hadn't yet spent would be
+    /// silently forgotten.
+    pub(crate) fn grow_window(&mut self, by: u32) {
+        self.window = self.window.saturating_add(by);
+        if let Some(task) = self.write_notify.take() {
+            task.notify();
+        }
+    }
+
+    /// Take the first error hit writing to the active recording's sink, if any.
+    pub(crate) fn recording_error(&mut self) -> Option<io::Error> {
+        self.recorder.as_mut().and_then(Recorder::take_error)
+    }
+}
+
+/// A sink that records channel I/O to an [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/)
+/// file, so the session can later be replayed with standard `asciinema` tooling.
+///
+/// See [`Channel::record`].
+pub(crate) struct Recorder {
+    writer: Box<Write>,
+    start: Instant,
+    // the first error we hit writing an event, if any; kept around until a caller asks for it via
+    // Channel::recording_error instead of being dropped on the floor, since `event` has nowhere
+    // else to report it (it's invoked for every chunk of channel I/O, not just at call sites that
+    // already return an io::Result)
+    error: Option<io::Error>,
+    // set alongside `error` and never cleared, so the recording stays disabled even after the
+    // error above has been taken and reported once
+    failed: bool,
+}
+
+impl Recorder {
+    fn new(mut writer: Box<Write>, width: u16, height: u16, term: &str) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        writeln!(
+            writer,
+            "{{\"version\":2,\"width\":{},\"height\":{},\"timestamp\":{},\"env\":{{\"TERM\":\"{}\"}}}}",
+            width, height, timestamp, json_escape(term)
+        )?;
+        Ok(Recorder {
+            writer,
+            start: Instant::now(),
+            error: None,
+            failed: false,
+        })
+    }
+
+    fn event(&mut self, code: u8, data: &[u8]) {
+        if self.failed {
+            // already broken; don't keep hammering a sink that's failing
+            return;
+        }
+
+        let elapsed = self.start.elapsed();
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+        if let Err(e) = writeln!(
+            self.writer,
+            "[{}, \"{}\", \"{}\"]",
+            secs,
+            code as char,
+            json_escape(&String::from_utf8_lossy(data))
+        ) {
+            self.failed = true;
+            self.error = Some(e);
         }
     }
+
+    /// Take the first error encountered writing to this recording's sink, if any, so it's only
+    /// ever reported once.
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+/// Escape a string for embedding in a JSON string literal.
+///
+/// This crate doesn't otherwise depend on a JSON library, and the asciicast v2 format only needs
+/// this one spot, so we do the minimal escaping ourselves rather than pull one in.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A pending pseudo-terminal request, to be issued right after the channel is opened and before
+/// the command is executed.
+struct PtyRequest<'a> {
+    term: &'a str,
+    cols: u32,
+    rows: u32,
+    pix_width: u32,
+    pix_height: u32,
+    modes: Vec<u8>,
+}
+
+/// Encode terminal modes as the opcode/`uint32` pairs the SSH protocol expects, terminated by the
+/// `TTY_OP_END` opcode (0). See [RFC 4254 section 8](https://tools.ietf.org/html/rfc4254#section-8).
+fn encode_pty_modes(modes: &[(u8, u32)]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(modes.len() * 5 + 1);
+    for &(opcode, value) in modes {
+        encoded.push(opcode);
+        encoded.extend_from_slice(&[
+            (value >> 24) as u8,
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ]);
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// What to do with a channel once it has finished opening.
+enum Action<'a> {
+    /// Run a command via `exec`.
+    Exec(&'a str),
+    /// Request an interactive shell.
+    Shell,
+    /// Start the named subsystem (e.g. `"sftp"`).
+    Subsystem(&'a str),
+    /// Nothing; the channel is ready to use as-is (e.g. a `direct-tcpip` channel).
+    None,
 }
 
 /// A newly opened, but not yet established channel.
 pub struct ChannelOpenFuture<'a, S: AsyncRead + AsyncWrite> {
-    cmd: &'a str,
+    action: Action<'a>,
     session: SharableConnection<S>,
     state: session::state::Ref,
     id: thrussh::ChannelId,
     first_round: bool,
+    pty: Option<PtyRequest<'a>>,
 }
 
 impl<'a, S: AsyncRead + AsyncWrite> ChannelOpenFuture<'a, S> {
@@ -57,17 +256,89 @@ impl<'a, S: AsyncRead + AsyncWrite> ChannelOpenFuture<'a, S> {
         id: thrussh::ChannelId,
     ) -> Self {
         ChannelOpenFuture {
-            cmd,
+            action: Action::Exec(cmd),
+            session,
+            state,
+            id,
+            first_round: true,
+            pty: None,
+        }
+    }
+
+    pub(crate) fn new_direct(
+        session: SharableConnection<S>,
+        state: session::state::Ref,
+        id: thrussh::ChannelId,
+    ) -> Self {
+        ChannelOpenFuture {
+            action: Action::None,
+            session,
+            state,
+            id,
+            first_round: true,
+            pty: None,
+        }
+    }
+
+    pub(crate) fn new_shell(
+        session: SharableConnection<S>,
+        state: session::state::Ref,
+        id: thrussh::ChannelId,
+    ) -> Self {
+        ChannelOpenFuture {
+            action: Action::Shell,
+            session,
+            state,
+            id,
+            first_round: true,
+            pty: None,
+        }
+    }
+
+    pub(crate) fn new_subsystem(
+        name: &'a str,
+        session: SharableConnection<S>,
+        state: session::state::Ref,
+        id: thrussh::ChannelId,
+    ) -> Self {
+        ChannelOpenFuture {
+            action: Action::Subsystem(name),
             session,
             state,
             id,
             first_round: true,
+            pty: None,
         }
     }
+
+    /// Request a pseudo-terminal be allocated for this channel before the command is executed.
+    ///
+    /// This is required for running interactive programs (shells, editors, anything that checks
+    /// `isatty`) over the channel. `modes` is a list of POSIX terminal mode opcodes and their
+    /// values (see `TTY_OP_*` in RFC 4254); pass an empty slice to request the server's defaults.
+    pub fn pty(
+        mut self,
+        term: &'a str,
+        cols: u32,
+        rows: u32,
+        pix_width: u32,
+        pix_height: u32,
+        modes: &[(u8, u32)],
+    ) -> Self {
+        self.pty = Some(PtyRequest {
+            term,
+            cols,
+            rows,
+            pix_width,
+            pix_height,
+            modes: encode_pty_modes(modes),
+        });
+        self
+    }
 }
 
 impl<'a, S: AsyncRead + AsyncWrite + thrussh::Tcp> Future for ChannelOpenFuture<'a, S> {
-    type Item = Channel;
+    type Item = Channel<S>;
     type Error = thrussh::HandlerError<()>;
 
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
@@ -87,7 +358,26 @@ impl<'a, S: AsyncRead + AsyncWrite + thrussh::Tcp> Future for ChannelOpenFuture<
                 {
                     let mut s = self.session.0.borrow_mut();
                     assert!(s.c.channel_is_open(self.id));
-                    s.c.exec(self.id, true, self.cmd);
+                    if let Some(ref pty) = self.pty {
+                        s.c.request_pty(
+                            self.id,
+                            true,
+                            pty.term,
+                            pty.cols,
+                            pty.rows,
+                            pty.pix_width,
+                            pty.pix_height,
+                            &pty.modes,
+                        );
+                        state.pty_dims = Some((pty.cols as u16, pty.rows as u16));
+                        state.pty_term = Some(pty.term.to_string());
+                    }
+                    match self.action {
+                        Action::Exec(cmd) => s.c.exec(self.id, true, cmd),
+                        Action::Shell => s.c.request_shell(self.id, true),
+                        Action::Subsystem(name) => s.c.request_subsystem(self.id, true, name),
+                        Action::None => {}
+                    }
                     // poke connection thread to say that we sent stuff
                     s.task.take().unwrap().notify();
                 }
@@ -95,6 +385,7 @@ impl<'a, S: AsyncRead + AsyncWrite + thrussh::Tcp> Future for ChannelOpenFuture<
                 Ok(Async::Ready(Channel {
                     state: self.state.clone(),
                     id: self.id,
+                    session: self.session.clone(),
                 }))
             }
             Some(Err(e)) => Err(thrussh::HandlerError::Error(thrussh::Error::IO(
@@ -109,9 +400,10 @@ impl<'a, S: AsyncRead + AsyncWrite + thrussh::Tcp> Future for ChannelOpenFuture<
 }
 
 /// A channel used to communicate with a process running at a remote host.
-pub struct Channel {
+pub struct Channel<S: AsyncRead + AsyncWrite> {
     state: session::state::Ref,
     id: thrussh::ChannelId,
+    session: SharableConnection<S>,
 }
 
 /// A future that will eventually resolve to the exit status of a process running on a remote host.
@@ -120,7 +412,7 @@ pub struct ExitStatusFuture {
     id: thrussh::ChannelId,
 }
 
-impl Channel {
+impl<S: AsyncRead + AsyncWrite> Channel<S> {
     /// Get the exit status of the remote process associated with this channel.
     pub fn exit_status(self) -> ExitStatusFuture {
         ExitStatusFuture {
@@ -128,10 +420,79 @@ impl Channel {
             id: self.id,
         }
     }
+
+    /// Get a handle to the remote process' standard error stream.
+    ///
+    /// Data written to `stderr` by the remote process is buffered separately from `stdout`, so
+    /// that reading from the [`Channel`] itself does not consume or get polluted by diagnostics
+    /// written to the process' error stream.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called more than once for the same [`Channel`]. The returned [`Stderr`] reads
+    /// from a buffer and wake-up slot shared by the channel; a second live handle would silently
+    /// steal bytes and notifications from the first.
+    pub fn stderr(&self) -> Stderr {
+        let mut s = self.state.borrow_mut();
+        let state = s.state_for
+            .get_mut(&self.id)
+            .expect("no state entry for valid channel");
+        assert!(
+            !state.stderr_taken,
+            "Channel::stderr called more than once for the same channel"
+        );
+        state.stderr_taken = true;
+        Stderr {
+            state: self.state.clone(),
+            id: self.id,
+        }
+    }
+
+    /// Record all I/O on this channel (both what the remote sends and what we write) to `writer`
+    /// in the [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) format, so the
+    /// session can be replayed later with standard `asciinema` tooling.
+    ///
+    /// The recording's width, height, and `TERM` are taken from this channel's PTY request, if
+    /// it made one (see [`ChannelOpenFuture::pty`]), and otherwise default to 80x24 and `xterm`.
+    pub fn record<W: Write + 'static>(&self, writer: W) -> io::Result<()> {
+        let mut s = self.state.borrow_mut();
+        let state = s.state_for
+            .get_mut(&self.id)
+            .expect("no state entry for valid channel");
+        let (width, height) = state.pty_dims.unwrap_or(DEFAULT_RECORDING_SIZE);
+        let term = state
+            .pty_term
+            .clone()
+            .unwrap_or_else(|| DEFAULT_RECORDING_TERM.to_string());
+        state.recorder = Some(Recorder::new(Box::new(writer), width, height, &term)?);
+        Ok(())
+    }
+
+    /// Check whether the recording started via [`Channel::record`], if any, is still healthy.
+    ///
+    /// Returns the first I/O error encountered writing to the recording sink since the last call
+    /// to this method, if any. Once returned, an error is considered reported and won't be
+    /// returned again; the recording is left disabled (no further events are written) after it
+    /// fails once.
+    pub fn recording_error(&self) -> Option<io::Error> {
+        let mut s = self.state.borrow_mut();
+        let state = s.state_for
+            .get_mut(&self.id)
+            .expect("no state entry for valid channel");
+        state.recording_error()
+    }
+}
+
+/// A handle to the standard error stream of a process running at a remote host.
+///
+/// See [`Channel::stderr`].
+pub struct Stderr {
+    state: session::state::Ref,
+    id: thrussh::ChannelId,
 }
 
 impl Future for ExitStatusFuture {
-    type Item = u32;
+    type Item = ExitStatus;
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
@@ -141,8 +502,8 @@ impl Future for ExitStatusFuture {
             .expect("no state entry for valid channel");
 
         state.exit_notify = None;
-        if let Some(e) = state.exit_status {
-            Ok(Async::Ready(e))
+        if let Some(ref e) = state.exit_status {
+            Ok(Async::Ready(e.clone()))
         } else if state.closed {
             Err(())
         } else {
@@ -152,7 +513,70 @@ impl Future for ExitStatusFuture {
     }
 }
 
-impl Read for Channel {
+/// How a remote process ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited normally with the given exit code.
+    Code(u32),
+    /// The process was terminated by a signal rather than exiting normally.
+    Signal {
+        /// The name of the signal that killed the process (e.g. `"TERM"`, without the `SIG`
+        /// prefix), as reported by the remote.
+        name: String,
+        /// Whether the process produced a core dump before terminating.
+        core_dumped: bool,
+        /// A human-readable message describing the event, as reported by the remote.
+        message: String,
+    },
+}
+
+/// Map a `thrussh::Sig` to the bare signal name (without the `SIG` prefix) it represents on the
+/// wire, per [RFC 4254 section 6.10](https://tools.ietf.org/html/rfc4254#section-6.10).
+///
+/// We match explicitly rather than `Debug`-formatting the enum, since `Sig::Custom` carries its
+/// own name string and would otherwise come out as `Custom("...")`.
+pub(crate) fn signal_name(sig: &thrussh::Sig) -> String {
+    match *sig {
+        thrussh::Sig::ABRT => "ABRT".to_string(),
+        thrussh::Sig::ALRM => "ALRM".to_string(),
+        thrussh::Sig::FPE => "FPE".to_string(),
+        thrussh::Sig::HUP => "HUP".to_string(),
+        thrussh::Sig::ILL => "ILL".to_string(),
+        thrussh::Sig::INT => "INT".to_string(),
+        thrussh::Sig::KILL => "KILL".to_string(),
+        thrussh::Sig::PIPE => "PIPE".to_string(),
+        thrussh::Sig::QUIT => "QUIT".to_string(),
+        thrussh::Sig::SEGV => "SEGV".to_string(),
+        thrussh::Sig::TERM => "TERM".to_string(),
+        thrussh::Sig::USR1 => "USR1".to_string(),
+        thrussh::Sig::USR2 => "USR2".to_string(),
+        thrussh::Sig::Custom(ref name) => name.clone(),
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExitStatus::Code(code) => write!(f, "{}", code),
+            ExitStatus::Signal {
+                ref name,
+                core_dumped,
+                ref message,
+            } => {
+                write!(f, "killed by signal SIG{}", name)?;
+                if core_dumped {
+                    write!(f, " (core dumped)")?;
+                }
+                if !message.is_empty() {
+                    write!(f, ": {}", message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Read for Channel<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let mut s = self.state.borrow_mut();
         let state = s.state_for
@@ -180,18 +604,230 @@ impl Read for Channel {
         }
     }
 }
-/*
-impl Write for Channel {
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        //
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for Channel<S> {}
+
+impl Read for Stderr {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut s = self.state.borrow_mut();
+        let state = s.state_for
+            .get_mut(&self.id)
+            .expect("no state entry for valid channel");
+        let n = ::std::cmp::min(buf.len(), state.ext_data.len() - state.ext_data_start);
+        (&mut buf[..n]).copy_from_slice(
+            &state.ext_data[state.ext_data_start..(state.ext_data_start + n)],
+        );
+
+        // see the NOTE in Read for Channel for why we don't just use Vec::drain here
+        state.ext_data_start += n;
+        if state.ext_data_start == state.ext_data.len() {
+            state.ext_data_start = 0;
+            state.ext_data.clear();
+        }
+
+        state.ext_read_notify = None;
+        if n == 0 && !state.eof {
+            state.ext_read_notify = Some(futures::task::current());
+            Err(io::Error::new(io::ErrorKind::WouldBlock, ""))
+        } else {
+            Ok(n)
+        }
+    }
+}
+
+impl AsyncRead for Stderr {}
+
+impl<S: AsyncRead + AsyncWrite + thrussh::Tcp> Channel<S> {
+    /// Tell the remote process that the terminal window size has changed.
+    ///
+    /// Only meaningful for channels that requested a pseudo-terminal; see
+    /// [`ChannelOpenFuture::pty`].
+    pub fn window_change(&self, cols: u32, rows: u32, pix_width: u32, pix_height: u32) {
+        let mut session = self.session.0.borrow_mut();
+        session.c.window_change(self.id, cols, rows, pix_width, pix_height);
+        if let Some(task) = session.task.take() {
+            task.notify();
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + thrussh::Tcp> Write for Channel<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            // don't block on, or spend window on, a zero-byte write
+            return Ok(0);
+        }
+
+        let n = {
+            let mut s = self.state.borrow_mut();
+            let state = s.state_for
+                .get_mut(&self.id)
+                .expect("no state entry for valid channel");
+
+            if state.closed {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "channel is closed"));
+            }
+
+            if state.window == 0 {
+                state.write_notify = Some(futures::task::current());
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, ""));
+            }
+
+            state.write_notify = None;
+            ::std::cmp::min(buf.len(), state.window as usize)
+        };
+
+        {
+            let mut session = self.session.0.borrow_mut();
+            session.c.data(self.id, None, &buf[..n]);
+            // poke connection thread to say that we sent stuff
+            session.task.take().unwrap().notify();
+        }
+
+        let mut s = self.state.borrow_mut();
+        let state = s.state_for
+            .get_mut(&self.id)
+            .expect("no state entry for valid channel");
+        state.window -= n as u32;
+        state.record_input(&buf[..n]);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + thrussh::Tcp> AsyncWrite for Channel<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        let mut s = self.state.borrow_mut();
+        let state = s.state_for
+            .get_mut(&self.id)
+            .expect("no state entry for valid channel");
+
+        if state.closed {
+            return Ok(Async::Ready(()));
+        }
+
+        let mut session = self.session.0.borrow_mut();
+        session.c.eof(self.id);
+        if let Some(task) = session.task.take() {
+            task.notify();
+        }
+
+        Ok(Async::Ready(()))
     }
-    fn flush(&mut self) -> Result<()> {}
 }
-*/
 
-impl AsyncRead for Channel {}
-/*
-impl AsyncWrite for Channel {
-    fn shutdown(&mut self) -> Poll<(), Error> {}
+#[cfg(test)]
+mod tests {
+    use super::{encode_pty_modes, json_escape, Recorder, State};
+    use futures::{self, Async, Future};
+    use std::io::{self, Write};
+
+    #[test]
+    fn json_escape_passes_through_plain_text() {
+        assert_eq!(json_escape("hello, world"), "hello, world");
+    }
+
+    #[test]
+    fn json_escape_escapes_quotes_and_backslashes() {
+        assert_eq!(json_escape(r#"say "hi"\ok"#), r#"say \"hi\"\\ok"#);
+    }
+
+    #[test]
+    fn json_escape_escapes_control_characters() {
+        assert_eq!(json_escape("a\nb\rc\td"), "a\\nb\\rc\\td");
+        assert_eq!(json_escape("\u{1}"), "\\u0001");
+    }
+
+    #[test]
+    fn encode_pty_modes_empty() {
+        // just the TTY_OP_END opcode
+        assert_eq!(encode_pty_modes(&[]), vec![0]);
+    }
+
+    #[test]
+    fn encode_pty_modes_packs_big_endian_u32s() {
+        // ECHO (53) off, then TTY_OP_END
+        assert_eq!(
+            encode_pty_modes(&[(53, 0)]),
+            vec![53, 0x00, 0x00, 0x00, 0x00, 0]
+        );
+        // ISPEED (128) = 38400, then TTY_OP_END
+        assert_eq!(
+            encode_pty_modes(&[(128, 38400)]),
+            vec![128, 0x00, 0x00, 0x96, 0x00, 0]
+        );
+    }
+
+    #[test]
+    fn encode_pty_modes_multiple() {
+        let encoded = encode_pty_modes(&[(1, 2), (3, 4)]);
+        assert_eq!(encoded, vec![1, 0, 0, 0, 2, 3, 0, 0, 0, 4, 0]);
+    }
+
+    #[test]
+    fn grow_window_adds_rather_than_replaces() {
+        // a window-adjust is a cumulative grant (RFC 4254 5.2): if we'd already spent part of
+        // the previous grant, the new bytes must be added on top of what's left, not overwrite it
+        let mut state = State::default();
+        state.window = 10;
+        state.grow_window(5);
+        assert_eq!(state.window, 15);
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "sink is gone"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn recorder_surfaces_write_failures_once_and_then_stays_disabled() {
+        // bypass Recorder::new (which would itself fail writing the header) so we can exercise
+        // event()'s error handling on its own
+        let mut recorder = Recorder {
+            writer: Box::new(FailingWriter),
+            start: ::std::time::Instant::now(),
+            error: None,
+            failed: false,
+        };
+        assert!(recorder.take_error().is_none());
+
+        recorder.event(b'o', b"hello");
+        assert!(recorder.take_error().is_some());
+        // already reported; asking again shouldn't resurface the same error
+        assert!(recorder.take_error().is_none());
+
+        // the sink stays disabled even after the error has been taken, rather than retrying (and
+        // failing against) the same broken writer on every subsequent event
+        recorder.event(b'o', b"world");
+        assert!(recorder.take_error().is_none());
+    }
+
+    #[test]
+    fn grow_window_wakes_a_pending_writer() {
+        let mut state = State::default();
+        state.window = 0;
+
+        // simulate a write that blocked because the window was exhausted, then have the remote
+        // grant more window; grow_window should take and notify the parked task
+        futures::future::poll_fn(|| -> Result<Async<()>, ()> {
+            state.write_notify = Some(futures::task::current());
+            state.grow_window(1);
+            Ok(Async::Ready(()))
+        }).wait()
+            .unwrap();
+
+        assert_eq!(state.window, 1);
+        assert!(state.write_notify.is_none());
+    }
 }
-*/
@@ -2,6 +2,7 @@ use tokio_io::{AsyncRead, AsyncWrite};
 use std::sync::Arc;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::path::PathBuf;
 use futures::Future;
 use tokio_core::reactor::Handle;
 use channel;
@@ -11,6 +12,41 @@ use thrussh_keys;
 
 pub(crate) mod state;
 
+/// A policy for deciding whether to trust a host key presented by the remote server during the
+/// SSH handshake.
+///
+/// See [`NewSession::verify_host_keys`].
+pub enum HostKeyVerifier {
+    /// Accept any host key without verification.
+    ///
+    /// This leaves the connection open to a man-in-the-middle attack, and should only be used
+    /// when the transport is already trusted by other means.
+    AcceptAny,
+    /// Accept the host key only if it is listed for this host in the given `known_hosts`-style
+    /// file.
+    KnownHosts(PathBuf),
+    /// Accept the host key only if the given function returns `true` for it.
+    Callback(Box<Fn(&thrussh_keys::key::PublicKey) -> bool>),
+}
+
+impl Default for HostKeyVerifier {
+    fn default() -> Self {
+        HostKeyVerifier::AcceptAny
+    }
+}
+
+impl HostKeyVerifier {
+    fn accepts(&self, host: &str, port: u16, key: &thrussh_keys::key::PublicKey) -> bool {
+        match *self {
+            HostKeyVerifier::AcceptAny => true,
+            HostKeyVerifier::KnownHosts(ref known_hosts) => {
+                thrussh_keys::check_known_hosts_path(host, port, key, known_hosts).unwrap_or(false)
+            }
+            HostKeyVerifier::Callback(ref accepts) => accepts(key),
+        }
+    }
+}
+
 /// A newly established, unauthenticated SSH session.
 ///
 /// All you can really do with this in authenticate it using one of the `authenticate_*` methods.
@@ -20,7 +56,33 @@ pub struct NewSession<S: AsyncRead + AsyncWrite> {
     handle: Handle,
 }
 
+/// A single prompt presented by the server during keyboard-interactive authentication.
+///
+/// See [`NewSession::authenticate_keyboard_interactive`].
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    /// The text of the prompt, to be shown to the user.
+    pub prompt: String,
+    /// Whether the user's response to this prompt should be echoed back as they type it.
+    pub echo: bool,
+}
+
 impl<S: AsyncRead + AsyncWrite + 'static> NewSession<S> {
+    /// Configure how the host key presented during the SSH handshake for `host:port` is
+    /// verified, before authenticating.
+    ///
+    /// If this is never called, [`HostKeyVerifier::AcceptAny`] is used, which accepts any host
+    /// key whatsoever and is thus vulnerable to man-in-the-middle attacks.
+    pub fn verify_host_keys(self, host: &str, port: u16, verifier: HostKeyVerifier) -> Self {
+        {
+            let handler = self.c.c.handler();
+            let mut state = handler.borrow_mut();
+            state.host = (host.to_string(), port);
+            state.host_key_verifier = verifier;
+        }
+        self
+    }
+
     /// Authenticate as the given user using the given keypair.
     ///
     /// See also
@@ -40,6 +102,82 @@ impl<S: AsyncRead + AsyncWrite + 'static> NewSession<S> {
                 .map(move |c| Session::make(Connection { c, task: None }, handle)),
         )
     }
+
+    /// Authenticate as the given user using a plaintext password.
+    ///
+    /// See also
+    /// [`thrussh::client::Connection::authenticate_password`](https://docs.rs/thrussh/0.19/thrussh/client/struct.Connection.html#method.authenticate_password).
+    pub fn authenticate_password(
+        self,
+        user: &str,
+        password: &str,
+    ) -> Box<Future<Item = Session<S>, Error = thrussh::HandlerError<()>>>
+    where
+        S: thrussh::Tcp,
+    {
+        let NewSession { c, handle } = self;
+        Box::new(
+            c.c
+                .authenticate_password(user, password)
+                .map(move |c| Session::make(Connection { c, task: None }, handle)),
+        )
+    }
+
+    /// Authenticate as the given user using identities offered by a running `ssh-agent`, as
+    /// pointed to by the `SSH_AUTH_SOCK` environment variable.
+    ///
+    /// See also
+    /// [`thrussh_keys::agent::client::AgentClient`](https://docs.rs/thrussh-keys/0.19/thrussh_keys/agent/client/struct.AgentClient.html).
+    pub fn authenticate_agent(
+        self,
+        user: &str,
+    ) -> Box<Future<Item = Session<S>, Error = thrussh::HandlerError<()>>>
+    where
+        S: thrussh::Tcp,
+    {
+        let NewSession { c, handle } = self;
+        let user = user.to_string();
+        Box::new(
+            thrussh_keys::agent::client::AgentClient::connect_env()
+                .map_err(thrussh::Error::IO)
+                .map_err(thrussh::HandlerError::Error)
+                .and_then(move |agent| c.c.authenticate_future(user, agent))
+                .map(move |c| Session::make(Connection { c, task: None }, handle)),
+        )
+    }
+
+    /// Authenticate as the given user via keyboard-interactive (`"keyboard-interactive"`)
+    /// authentication.
+    ///
+    /// `submethods` is passed to the server as a hint for which sub-methods it should use (an
+    /// empty string lets the server choose). `responder` is invoked once per info-request round
+    /// with the prompts the server sent, and should return one response per prompt, in order.
+    pub fn authenticate_keyboard_interactive<F>(
+        self,
+        user: &str,
+        submethods: &str,
+        mut responder: F,
+    ) -> Box<Future<Item = Session<S>, Error = thrussh::HandlerError<()>>>
+    where
+        S: thrussh::Tcp,
+        F: FnMut(&[Prompt]) -> Vec<String> + 'static,
+    {
+        let NewSession { c, handle } = self;
+        Box::new(
+            c.c
+                .authenticate_keyboard_interactive(user, submethods, move |prompts| {
+                    let prompts: Vec<Prompt> = prompts
+                        .iter()
+                        .map(|&(ref prompt, echo)| Prompt {
+                            prompt: prompt.clone(),
+                            echo,
+                        })
+                        .collect();
+                    responder(&prompts)
+                })
+                .map(move |c| Session::make(Connection { c, task: None }, handle)),
+        )
+    }
 }
 
 /// An established and authenticated SSH session.
@@ -85,22 +223,121 @@ impl<S: AsyncRead + AsyncWrite + thrussh::Tcp + 'static> Session<S> {
         state.errored_with.take()
     }
 
+    /// Record a freshly opened channel's state, keyed by its `ChannelId`, so the handler can
+    /// find it once the connection task starts driving the open confirmation/data/etc. events.
+    fn register_channel(&self, channel_id: thrussh::ChannelId) -> state::Ref {
+        let session = (self.0).0.borrow();
+        let state = session.c.handler().clone();
+        state
+            .borrow_mut()
+            .state_for
+            .insert(channel_id, channel::State::default());
+        state
+    }
+
+    /// Open a new channel by calling `open` on the underlying connection, then register its
+    /// state so the handler can find it once the connection task starts driving the open
+    /// confirmation/data/etc. events.
+    fn open_channel<F, E>(&self, open: F) -> (thrussh::ChannelId, state::Ref)
+    where
+        F: FnOnce(&mut thrussh::client::Connection<S, state::Ref>)
+            -> Result<thrussh::ChannelId, E>,
+        E: ::std::fmt::Debug,
+    {
+        let channel_id = {
+            let mut session = (self.0).0.borrow_mut();
+            open(&mut session.c).expect("sessions are always authenticated")
+        };
+        let state = self.register_channel(channel_id);
+        (channel_id, state)
+    }
+
     /// Establish a new channel over this session to execute the given command.
     ///
     /// Note that any errors encountered while operating on the channel after it has been opened
     /// will manifest only as reads or writes no longer succeeding. To get the underlying error,
     /// call [`Session::last_error`].
     pub fn open_exec<'a>(&mut self, cmd: &'a str) -> channel::ChannelOpenFuture<'a, S> {
-        let mut session = (self.0).0.borrow_mut();
-        let state = session.c.handler().clone();
-
-        let channel_id = (&mut *session.c)
-            .channel_open_session()
-            .expect("sessions are always authenticated");
-        state
-            .borrow_mut()
-            .state_for
-            .insert(channel_id, channel::State::default());
+        let (channel_id, state) = self.open_channel(|c| c.channel_open_session());
         channel::ChannelOpenFuture::new(cmd, self.0.clone(), state, channel_id)
     }
+
+    /// Establish a new channel over this session and request an interactive shell on it.
+    ///
+    /// Chain [`ChannelOpenFuture::pty`] onto the result to allocate a pseudo-terminal first, as
+    /// most shells expect one.
+    pub fn open_shell<'a>(&mut self) -> channel::ChannelOpenFuture<'a, S> {
+        let (channel_id, state) = self.open_channel(|c| c.channel_open_session());
+        channel::ChannelOpenFuture::new_shell(self.0.clone(), state, channel_id)
+    }
+
+    /// Establish a new channel over this session and start the named subsystem on it, e.g.
+    /// `"sftp"`.
+    ///
+    /// This is how protocols that are layered on top of SSH but aren't a plain shell command
+    /// (SFTP, LSP-over-SSH, NETCONF, ...) get their transport: the server starts the
+    /// corresponding program and speaks its protocol directly over the channel's stdin/stdout.
+    pub fn open_subsystem<'a>(&mut self, name: &'a str) -> channel::ChannelOpenFuture<'a, S> {
+        let (channel_id, state) = self.open_channel(|c| c.channel_open_session());
+        channel::ChannelOpenFuture::new_subsystem(name, self.0.clone(), state, channel_id)
+    }
+
+    /// Establish a new `direct-tcpip` channel over this session.
+    ///
+    /// This is what SSH clients use for local port forwarding and jump-host tunneling: the
+    /// server connects to `(target_host, target_port)` on your behalf, and the resulting
+    /// [`Channel`] gives you a bidirectional byte stream to it, which you can splice to a local
+    /// `TcpStream`. `originator_host` and `originator_port` are reported to the server as the
+    /// origin of the connection, and are typically informational only.
+    pub fn open_direct_tcpip<'a>(
+        &mut self,
+        target_host: &str,
+        target_port: u32,
+        originator_host: &str,
+        originator_port: u32,
+    ) -> channel::ChannelOpenFuture<'a, S> {
+        let (channel_id, state) = self.open_channel(|c| {
+            c.channel_open_direct_tcpip(target_host, target_port, originator_host, originator_port)
+        });
+        channel::ChannelOpenFuture::new_direct(self.0.clone(), state, channel_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostKeyVerifier;
+    use std::path::PathBuf;
+    use thrussh_keys;
+
+    // a syntactically valid ssh-ed25519 public key; its bytes are arbitrary, since none of the
+    // tests below rely on it actually verifying against anything
+    const TEST_KEY_BASE64: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIAABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhscHR4f";
+
+    fn test_key() -> thrussh_keys::key::PublicKey {
+        thrussh_keys::parse_public_key_base64(TEST_KEY_BASE64).expect("valid test key")
+    }
+
+    #[test]
+    fn accept_any_is_the_default() {
+        let verifier = HostKeyVerifier::default();
+        assert!(verifier.accepts("example.com", 22, &test_key()));
+    }
+
+    #[test]
+    fn callback_returning_false_rejects() {
+        let verifier = HostKeyVerifier::Callback(Box::new(|_key| false));
+        assert!(!verifier.accepts("example.com", 22, &test_key()));
+    }
+
+    #[test]
+    fn callback_returning_true_accepts() {
+        let verifier = HostKeyVerifier::Callback(Box::new(|_key| true));
+        assert!(verifier.accepts("example.com", 22, &test_key()));
+    }
+
+    #[test]
+    fn known_hosts_with_missing_file_rejects() {
+        let verifier = HostKeyVerifier::KnownHosts(PathBuf::from("/no/such/known_hosts"));
+        assert!(!verifier.accepts("example.com", 22, &test_key()));
+    }
 }
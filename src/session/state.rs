@@ -6,11 +6,17 @@ use futures;
 use thrussh_keys;
 use thrussh;
 use channel;
+use session::HostKeyVerifier;
 
 #[derive(Default)]
 pub(crate) struct Inner {
     pub(crate) state_for: HashMap<thrussh::ChannelId, channel::State>,
     pub(crate) errored_with: Option<thrussh::HandlerError<()>>,
+
+    // the host (and port) we believe we're connecting to, and how to decide whether to trust the
+    // key it presents; set via NewSession::verify_host_keys before authentication
+    pub(crate) host: (String, u16),
+    pub(crate) host_key_verifier: HostKeyVerifier,
 }
 
 #[derive(Default, Clone)]
@@ -30,8 +36,13 @@ impl thrussh::client::Handler for Ref {
     type FutureSign = futures::Finished<(Self, thrussh::CryptoVec), Self::Error>;
     type SessionUnit = futures::Finished<(Self, thrussh::client::Session), Self::Error>;
 
-    fn check_server_key(self, _: &thrussh_keys::key::PublicKey) -> Self::FutureBool {
-        futures::finished((self, true))
+    fn check_server_key(self, key: &thrussh_keys::key::PublicKey) -> Self::FutureBool {
+        let trusted = {
+            let state = self.0.borrow();
+            let (ref host, port) = state.host;
+            state.host_key_verifier.accepts(host, port, key)
+        };
+        futures::finished((self, trusted))
     }
 
     fn channel_open_confirmation(
@@ -86,19 +97,26 @@ impl thrussh::client::Handler for Ref {
         data: &[u8],
         session: thrussh::client::Session,
     ) -> Self::SessionUnit {
-        if ext.is_none() {
+        {
             let mut state = self.0.borrow_mut();
             let state = state
                 .state_for
                 .get_mut(&channel)
                 .expect("got data for unknown channel");
 
-            state.data.extend(data);
-            if let Some(task) = state.read_notify.take() {
-                task.notify();
+            if ext == Some(1) {
+                state.record_output(data);
+                state.ext_data.extend(data);
+                if let Some(task) = state.ext_read_notify.take() {
+                    task.notify();
+                }
+            } else if ext.is_none() {
+                state.record_output(data);
+                state.data.extend(data);
+                if let Some(task) = state.read_notify.take() {
+                    task.notify();
+                }
             }
-        } else {
-            // TODO: stderr
         }
 
         futures::finished((self, session))
@@ -121,9 +139,16 @@ impl thrussh::client::Handler for Ref {
             if let Some(task) = state.read_notify.take() {
                 task.notify();
             }
+            // also wake a parked Channel::stderr reader, so it doesn't hang forever on EOF/close
+            if let Some(task) = state.ext_read_notify.take() {
+                task.notify();
+            }
             if let Some(task) = state.exit_notify.take() {
                 task.notify();
             }
+            if let Some(task) = state.write_notify.take() {
+                task.notify();
+            }
         }
 
         futures::finished((self, session))
@@ -145,6 +170,13 @@ impl thrussh::client::Handler for Ref {
             if let Some(task) = state.read_notify.take() {
                 task.notify();
             }
+            // also wake a parked Channel::stderr reader, so it doesn't hang forever on EOF/close
+            if let Some(task) = state.ext_read_notify.take() {
+                task.notify();
+            }
+            if let Some(task) = state.write_notify.take() {
+                task.notify();
+            }
         }
 
         futures::finished((self, session))
@@ -163,7 +195,7 @@ impl thrussh::client::Handler for Ref {
                 .get_mut(&channel)
                 .expect("got data for unknown channel");
 
-            state.exit_status = Some(exit_status);
+            state.exit_status = Some(channel::ExitStatus::Code(exit_status));
             if let Some(task) = state.exit_notify.take() {
                 task.notify();
             }
@@ -171,4 +203,52 @@ impl thrussh::client::Handler for Ref {
 
         futures::finished((self, session))
     }
+
+    fn exit_signal(
+        self,
+        channel: thrussh::ChannelId,
+        signal_name: thrussh::Sig,
+        core_dumped: bool,
+        error_message: &str,
+        _lang_tag: &str,
+        session: thrussh::client::Session,
+    ) -> Self::SessionUnit {
+        {
+            let mut state = self.0.borrow_mut();
+            let state = state
+                .state_for
+                .get_mut(&channel)
+                .expect("got data for unknown channel");
+
+            state.exit_status = Some(channel::ExitStatus::Signal {
+                name: channel::signal_name(&signal_name),
+                core_dumped,
+                message: error_message.to_string(),
+            });
+            if let Some(task) = state.exit_notify.take() {
+                task.notify();
+            }
+        }
+
+        futures::finished((self, session))
+    }
+
+    fn window_adjusted(
+        self,
+        channel: thrussh::ChannelId,
+        bytes_added: u32,
+        session: thrussh::client::Session,
+    ) -> Self::SessionUnit {
+        {
+            let mut state = self.0.borrow_mut();
+            let state = state
+                .state_for
+                .get_mut(&channel)
+                .expect("got data for unknown channel");
+
+            state.grow_window(bytes_added);
+        }
+
+        futures::finished((self, session))
+    }
 }